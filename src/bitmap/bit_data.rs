@@ -5,10 +5,69 @@ use super::info_header::InfoHeader;
 use super::rgb_quad::RgbQuad;
 use super::rgba::Rgba;
 
+///
+/// The `BI_BITFIELDS` per-channel masks for a 16- or 32-bit direct-color
+/// bitmap. Each mask picks out the bits of a pixel unit that belong to that
+/// channel; `alpha` of `0` means the format carries no alpha channel.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelMasks {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub alpha: u32,
+}
+
+/// Largest width or height `stream` will accept. Comfortably above any
+/// legitimate bitmap but small enough to keep a crafted header from forcing
+/// a multi-gigabyte allocation.
+const MAX_DIMENSION: u32 = 1 << 16;
+
+///
+/// Reasons `BitData::stream` can refuse to decode a bitmap's pixel data.
+///
+#[derive(Debug)]
+pub enum BitDataError {
+    /// `width` or `height` was zero or larger than `MAX_DIMENSION`.
+    DimensionTooLarge { width: u32, height: u32 },
+    /// The expected pixel data size overflowed while computing it.
+    SizeOverflow,
+    /// `off_bits` plus the expected pixel data size ran past the end of the
+    /// supplied buffer.
+    DataOutOfBounds { required: usize, available: usize },
+}
+
+impl std::fmt::Display for BitDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BitDataError::DimensionTooLarge { width, height } => write!(
+                f,
+                "image dimensions {}x{} are not supported",
+                width, height
+            ),
+            BitDataError::SizeOverflow => {
+                write!(f, "pixel data size overflowed while validating the header")
+            }
+            BitDataError::DataOutOfBounds {
+                required,
+                available,
+            } => write!(
+                f,
+                "pixel data requires {} bytes starting at off_bits, but only {} bytes are available",
+                required, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BitDataError {}
+
 ///
 /// Used for working with binary data when the image is read in or converted to
 /// a bit map with a bit depth of 1, 4, or 8. Each byte points to a color inside
-/// of colors.
+/// of colors. `BI_BITFIELDS` images (bit depth 16 or 32) are the exception:
+/// there `bytes` holds packed channel values directly and `channel_masks`
+/// is set, see `from_bitfields`.
 ///
 pub struct BitData {
     /// width of the image
@@ -23,11 +82,22 @@ pub struct BitData {
     bytes: Vec<u8>,
     /// bit depth of the image
     bit_depth: BitDepth,
+    /// set for `BI_BITFIELDS` images, where `bytes` holds packed channel
+    /// values rather than indexes into `colors`
+    channel_masks: Option<ChannelMasks>,
+    /// `true` if rows are stored top-down (a negative `biHeight`), `false`
+    /// for the conventional bottom-up layout
+    top_down: bool,
 }
 
 impl BitData {
     ///
-    /// Create bit data from stream of bytes
+    /// Create bit data from stream of bytes.
+    ///
+    /// Validates the declared dimensions and `off_bits` offset against
+    /// `bit_stream` before copying anything, so a crafted or truncated file
+    /// is reported as an error instead of causing an out-of-range slice, a
+    /// huge allocation, or a later panic in `as_rgba`.
     ///
     pub fn stream(
         bit_stream: &[u8],
@@ -35,98 +105,203 @@ impl BitData {
         info: &InfoHeader,
         bit_depth: BitDepth,
         colors: &RgbQuad,
-    ) -> BitData {
-        let offset = file.get_off_bits() as usize;
-        // for byte in offset..bits
-        let mut bytes = Vec::new();
-        for index in offset..bit_stream.len() {
-            bytes.push(bit_stream[index]);
+    ) -> Result<BitData, BitDataError> {
+        let width = info.get_width();
+        let height = info.get_height();
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(BitDataError::DimensionTooLarge { width, height });
         }
-        BitData {
-            width: info.get_width(),
-            height: info.get_height(),
+
+        let step = bit_depth.get_step_counter();
+        let row_bytes = Self::padded_row_bytes(width, step) as u64;
+        let expected_len = row_bytes
+            .checked_mul(height as u64)
+            .ok_or(BitDataError::SizeOverflow)?;
+
+        let offset = file.get_off_bits() as u64;
+        let required_end = offset
+            .checked_add(expected_len)
+            .ok_or(BitDataError::SizeOverflow)?;
+        if required_end > bit_stream.len() as u64 {
+            return Err(BitDataError::DataOutOfBounds {
+                required: required_end as usize,
+                available: bit_stream.len(),
+            });
+        }
+
+        let offset = offset as usize;
+        let expected_len = expected_len as usize;
+        let mut bytes = Vec::with_capacity(expected_len);
+        bytes.extend_from_slice(&bit_stream[offset..offset + expected_len]);
+
+        Ok(BitData {
+            width,
+            height,
             bit_depth,
             colors: colors.clone_colors(),
             bytes,
-        }
+            channel_masks: None,
+            top_down: info.is_top_down(),
+        })
     }
 
     ///
-    /// Create bit data from a bitmap
+    /// Create bit data from a bitmap, storing rows bottom-up — the
+    /// conventional BMP layout. Use `from_bitmap_oriented` to write rows
+    /// top-down instead.
     ///
     pub fn from_bitmap(bitmap: &BitMap, bit_depth: BitDepth) -> BitData {
+        Self::from_bitmap_oriented(bitmap, bit_depth, false)
+    }
+
+    ///
+    /// Create bit data from a bitmap, writing rows in the order `bitmap`
+    /// stores them (`top_down = true`) or reversed into the conventional
+    /// bottom-up BMP layout (`top_down = false`).
+    ///
+    pub fn from_bitmap_oriented(bitmap: &BitMap, bit_depth: BitDepth, top_down: bool) -> BitData {
         let mut unique_colors = bitmap.get_all_unique_colors().clone();
         unique_colors.push(Rgba::rgb(0, 0, 0));
         let step = bit_depth.get_step_counter();
 
-        // figure out how much padding is on each row
-        // this is needed because for each row of a bmp image needs to finish
-        // with a width of bytes that is divisible by 4. Here we are figuring out
-        // how much bit padding and byte padding we need.
-        let bit_width = bitmap.get_width() * bit_depth.get_step_counter();
-        let bit_padding = match bit_width % 8 {
-            0 => 0,
-            _ => 8 - (bit_width % 8),
+        // a palette depth of `step` bits can only address 2^step colors; if
+        // the image has more than that, reduce it with median-cut
+        // quantization instead of panicking on the first pixel that doesn't
+        // fit in the color table.
+        let capacity = 1usize << step;
+        let palette = if unique_colors.len() > capacity {
+            Self::median_cut_palette(&unique_colors, capacity)
+        } else {
+            unique_colors
         };
-        let byte_width = (bit_width + bit_padding) / 8;
-        let byte_padding = match byte_width % 4 {
-            0 => 0,
-            _ => 4 - (byte_width % 4),
-        };
-        let mut bytes =
-            Vec::with_capacity(((byte_width + byte_padding) * bitmap.get_height()) as usize);
 
-        let step = step as u8;
-        let mut byte: u8 = 0;
-        let mut counter: u32 = 0;
-        let mut shift: u32 = 0;
-        for i in 0..bitmap.get_pixels().len() {
-            let pixel = bitmap.get_pixels()[i];
-            let color_index = unique_colors.iter().position(|&c| c == pixel).unwrap() as u8;
-            counter += step as u32;
-            shift = counter % 8;
-            byte = byte << step;
-            // if bit_depth is a BW then we want to push the bit onto the byte
-            byte += color_index;
-
-            // push byte into data
-            if shift == 0 && i != 0 && bit_width >= 8 {
-                bytes.push(byte);
-                byte = 0;
-            }
-            // add padding to row
-            if counter % bitmap.get_width() == 0 && i != 0 {
-                if bit_padding != 0 {
-                    byte = byte << bit_padding;
-                    bytes.push(byte);
-                    byte = 0;
-                    counter = 0;
-                }
+        let width = bitmap.get_width();
+        let height = bitmap.get_height();
+        let pixels = bitmap.get_pixels();
+        let row_bytes = Self::padded_row_bytes(width, step);
+        let mut bytes = Vec::with_capacity(row_bytes * height as usize);
 
-                for _ in 0..byte_padding {
-                    bytes.push(0);
-                }
-            }
-        }
-        if shift != 0 {
-            byte = byte << (8 - shift);
-            bytes.push(byte);
-        }
-        if bytes.len() % 4 != 0 {
-            for _ in 0..byte_padding {
-                bytes.push(0);
-            }
+        let rows: Box<dyn Iterator<Item = u32>> = if top_down {
+            Box::new(0..height)
+        } else {
+            Box::new((0..height).rev())
+        };
+        for row in rows {
+            let start = (row * width) as usize;
+            let end = start + width as usize;
+            let indexes: Vec<u8> = pixels[start..end]
+                .iter()
+                .map(|&pixel| Self::palette_index(&palette, pixel))
+                .collect();
+            Self::pack_row(&indexes, step, &mut bytes);
         }
 
         BitData {
-            width: bitmap.get_width(),
-            height: bitmap.get_height(),
+            width,
+            height,
             bit_depth,
-            colors: unique_colors,
+            colors: palette,
             bytes,
+            channel_masks: None,
+            top_down,
         }
     }
 
+    /// Look up `pixel`'s index in `palette`, falling back to the closest
+    /// color by Euclidean distance when the palette has been quantized and
+    /// no exact match exists.
+    fn palette_index(palette: &[Rgba], pixel: Rgba) -> u8 {
+        if let Some(index) = palette.iter().position(|&c| c == pixel) {
+            return index as u8;
+        }
+        palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &c)| Self::color_distance(c, pixel))
+            .map(|(index, _)| index as u8)
+            .unwrap()
+    }
+
+    fn color_distance(a: Rgba, b: Rgba) -> u32 {
+        let dr = a.get_red() as i32 - b.get_red() as i32;
+        let dg = a.get_green() as i32 - b.get_green() as i32;
+        let db = a.get_blue() as i32 - b.get_blue() as i32;
+        (dr * dr + dg * dg + db * db) as u32
+    }
+
+    ///
+    /// Reduce `colors` to at most `capacity` entries using median-cut
+    /// quantization: repeatedly split the box with the widest channel range
+    /// at the median along that channel, then average each final box down
+    /// to a single palette color.
+    ///
+    fn median_cut_palette(colors: &[Rgba], capacity: usize) -> Vec<Rgba> {
+        let mut boxes: Vec<Vec<Rgba>> = vec![colors.to_vec()];
+
+        while boxes.len() < capacity {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (i, Self::widest_channel(b)))
+                .max_by_key(|(_, (_, range))| *range);
+            let (box_index, (channel, _)) = match widest {
+                Some(found) => found,
+                None => break,
+            };
+
+            let mut candidate = boxes.remove(box_index);
+            if candidate.len() < 2 {
+                boxes.push(candidate);
+                break;
+            }
+            candidate.sort_by_key(|c| match channel {
+                0 => c.get_red(),
+                1 => c.get_green(),
+                _ => c.get_blue(),
+            });
+            let upper = candidate.split_off(candidate.len() / 2);
+            boxes.push(candidate);
+            boxes.push(upper);
+        }
+
+        boxes.iter().map(|b| Self::average_color(b)).collect()
+    }
+
+    /// Returns the channel (0 = red, 1 = green, 2 = blue) with the widest
+    /// value range in `colors`, along with that range.
+    fn widest_channel(colors: &[Rgba]) -> (u8, u32) {
+        let (mut r_min, mut r_max) = (255u8, 0u8);
+        let (mut g_min, mut g_max) = (255u8, 0u8);
+        let (mut b_min, mut b_max) = (255u8, 0u8);
+        for c in colors {
+            r_min = r_min.min(c.get_red());
+            r_max = r_max.max(c.get_red());
+            g_min = g_min.min(c.get_green());
+            g_max = g_max.max(c.get_green());
+            b_min = b_min.min(c.get_blue());
+            b_max = b_max.max(c.get_blue());
+        }
+        let ranges: [(u8, u32); 3] = [
+            (0, (r_max - r_min) as u32),
+            (1, (g_max - g_min) as u32),
+            (2, (b_max - b_min) as u32),
+        ];
+        *ranges.iter().max_by_key(|(_, range)| *range).unwrap()
+    }
+
+    /// Average the channels of `colors` into a single representative color.
+    fn average_color(colors: &[Rgba]) -> Rgba {
+        let (mut r, mut g, mut b, mut a) = (0u32, 0u32, 0u32, 0u32);
+        for c in colors {
+            r += c.get_red() as u32;
+            g += c.get_green() as u32;
+            b += c.get_blue() as u32;
+            a += c.get_alpha() as u32;
+        }
+        let n = colors.len() as u32;
+        Rgba::rgba((r / n) as u8, (g / n) as u8, (b / n) as u8, (a / n) as u8)
+    }
+
     ///
     /// Pass the bit data back as a stream of bytes
     ///
@@ -138,6 +313,10 @@ impl BitData {
     /// Convert bits into array of colors
     ///
     pub fn as_rgba(&self) -> Vec<Rgba> {
+        if let Some(masks) = &self.channel_masks {
+            return self.as_rgba_bitfields(masks);
+        }
+
         let mut pixels = Vec::new();
         let step = self.bit_depth.get_step_counter();
         // figure out how much padding is on each row
@@ -200,6 +379,17 @@ impl BitData {
                 byte_padding_counter = byte_padding;
             }
         }
+
+        // rows are stored bottom-up unless the info header's height was
+        // negative; `pixels` should always come out top-to-bottom
+        if !self.top_down {
+            let width = self.width as usize;
+            let mut top_to_bottom = Vec::with_capacity(pixels.len());
+            for row in pixels.chunks(width).rev() {
+                top_to_bottom.extend_from_slice(row);
+            }
+            pixels = top_to_bottom;
+        }
         pixels
     }
 
@@ -210,6 +400,456 @@ impl BitData {
     pub fn get_bytes_size(&self) -> u32 {
         self.bytes.len() as u32
     }
+
+    ///
+    /// The palette this data's bytes index into. Empty for `BI_BITFIELDS`
+    /// direct-color data.
+    ///
+    pub fn get_colors(&self) -> &[Rgba] {
+        &self.colors
+    }
+
+    ///
+    /// Create bit data by decoding a BI_RLE8 or BI_RLE4 compressed stream.
+    ///
+    /// The compressed stream is expanded into the same row-padded,
+    /// packed-index layout that `stream` produces for uncompressed
+    /// bitmaps, so `as_rgba` works unchanged on the result. Unlike
+    /// uncompressed data the compressed length isn't known up front, but
+    /// the declared dimensions and `off_bits` offset are validated the same
+    /// way `stream` validates them, since a malformed run can otherwise
+    /// walk the decoder off the end of `bit_stream`.
+    ///
+    pub fn from_rle_stream(
+        bit_stream: &[u8],
+        file: &FileHeader,
+        info: &InfoHeader,
+        bit_depth: BitDepth,
+        colors: &RgbQuad,
+    ) -> Result<BitData, BitDataError> {
+        let width = info.get_width();
+        let height = info.get_height();
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(BitDataError::DimensionTooLarge { width, height });
+        }
+
+        let step = bit_depth.get_step_counter();
+        let offset = file.get_off_bits() as usize;
+        if offset > bit_stream.len() {
+            return Err(BitDataError::DataOutOfBounds {
+                required: offset,
+                available: bit_stream.len(),
+            });
+        }
+        let data = &bit_stream[offset..];
+
+        let mut rows: Vec<Vec<u8>> = vec![vec![0u8; width as usize]; height as usize];
+        let mut x: usize = 0;
+        let mut y: usize = 0;
+        let mut i: usize = 0;
+        while i + 1 < data.len() {
+            // a malformed/truncated stream can drive `y` past the last row
+            // (a bad delta or a run that never hits an end-of-line marker);
+            // there's nowhere left to write, so stop decoding.
+            if y >= rows.len() {
+                break;
+            }
+
+            let first = data[i];
+            let second = data[i + 1];
+            i += 2;
+            if first != 0 {
+                // encoded run: `first` copies of the index(es) in `second`
+                let run_length = first as usize;
+                if step == 4 {
+                    let indexes = [second >> 4, second & 0x0F];
+                    for pixel in 0..run_length {
+                        if x >= width as usize {
+                            break;
+                        }
+                        rows[y][x] = indexes[pixel % 2];
+                        x += 1;
+                    }
+                } else {
+                    for _ in 0..run_length {
+                        if x >= width as usize {
+                            break;
+                        }
+                        rows[y][x] = second;
+                        x += 1;
+                    }
+                }
+                continue;
+            }
+
+            // escape sequences
+            match second {
+                0x00 => {
+                    // end of line
+                    x = 0;
+                    y += 1;
+                }
+                0x01 => break, // end of bitmap
+                0x02 => {
+                    // delta: skip the given number of pixels (left as index 0)
+                    if i + 1 >= data.len() {
+                        break;
+                    }
+                    x += data[i] as usize;
+                    y += data[i + 1] as usize;
+                    i += 2;
+                }
+                absolute_count => {
+                    // absolute mode: `absolute_count` literal indexes follow
+                    let count = absolute_count as usize;
+                    let bytes_used = if step == 4 { (count + 1) / 2 } else { count };
+                    if i + bytes_used > data.len() {
+                        // the stream is truncated mid-run; nothing more to decode
+                        break;
+                    }
+                    for pixel in 0..count {
+                        if y >= rows.len() || x >= width as usize {
+                            break;
+                        }
+                        let index = if step == 4 {
+                            let byte = data[i + pixel / 2];
+                            if pixel % 2 == 0 {
+                                byte >> 4
+                            } else {
+                                byte & 0x0F
+                            }
+                        } else {
+                            data[i + pixel]
+                        };
+                        rows[y][x] = index;
+                        x += 1;
+                    }
+                    // absolute runs are padded so they end on a 16-bit boundary
+                    i += bytes_used + (bytes_used % 2);
+                }
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(Self::padded_row_bytes(width, step) * height as usize);
+        for row in &rows {
+            Self::pack_row(row, step, &mut bytes);
+        }
+
+        Ok(BitData {
+            width,
+            height,
+            bit_depth,
+            colors: colors.clone_colors(),
+            bytes,
+            channel_masks: None,
+            top_down: false,
+        })
+    }
+
+    ///
+    /// Encode the indexed pixel data as a BI_RLE8/BI_RLE4 compressed stream,
+    /// picking whichever of encoded or absolute mode is shorter for each run.
+    ///
+    pub fn as_rle_bytes(&self) -> Vec<u8> {
+        let step = self.bit_depth.get_step_counter();
+        let row_bytes = Self::padded_row_bytes(self.width, step);
+        let mut out = Vec::new();
+
+        for row in self.bytes.chunks(row_bytes) {
+            let indexes = Self::unpack_row(row, self.width, step);
+            Self::encode_row(&indexes, step, &mut out);
+            out.push(0x00);
+            out.push(0x00); // end of line
+        }
+        out.push(0x00);
+        out.push(0x01); // end of bitmap
+        out
+    }
+
+    /// Width in bytes of a row padded up to a 4-byte boundary.
+    fn padded_row_bytes(width: u32, step: u32) -> usize {
+        let bit_width = width * step;
+        let byte_width = (bit_width + 7) / 8;
+        (((byte_width + 3) / 4) * 4) as usize
+    }
+
+    /// Unpack a single (possibly padded) row of packed indexes into one
+    /// index per pixel.
+    fn unpack_row(row: &[u8], width: u32, step: u32) -> Vec<u8> {
+        let per_byte = 8 / step;
+        let mut indexes = Vec::with_capacity(width as usize);
+        'row: for byte in row {
+            for slot in (0..per_byte).rev() {
+                if indexes.len() as u32 == width {
+                    break 'row;
+                }
+                let shift = slot * step;
+                let mask = (1u32 << step) - 1;
+                indexes.push(((*byte as u32 >> shift) & mask) as u8);
+            }
+        }
+        indexes
+    }
+
+    /// Pack one row of indexes (one byte per pixel) into `step`-bit wide
+    /// entries, padding the row out to a 4-byte boundary.
+    fn pack_row(indexes: &[u8], step: u32, bytes: &mut Vec<u8>) {
+        let per_byte = 8 / step;
+        let start = bytes.len();
+        let mut byte: u8 = 0;
+        let mut filled = 0;
+        for &index in indexes {
+            byte = (byte << step) | index;
+            filled += 1;
+            if filled == per_byte {
+                bytes.push(byte);
+                byte = 0;
+                filled = 0;
+            }
+        }
+        if filled != 0 {
+            byte <<= step * (per_byte - filled);
+            bytes.push(byte);
+        }
+        let row_len = Self::padded_row_bytes(indexes.len() as u32, step);
+        while bytes.len() - start < row_len {
+            bytes.push(0);
+        }
+    }
+
+    /// RLE-encode a single row of indexes, choosing the shorter of encoded
+    /// runs (same index repeated) and absolute runs (literal indexes) as it
+    /// scans left to right.
+    fn encode_row(indexes: &[u8], step: u32, out: &mut Vec<u8>) {
+        let mut i = 0;
+        while i < indexes.len() {
+            let mut run_len = 1;
+            while i + run_len < indexes.len()
+                && indexes[i + run_len] == indexes[i]
+                && run_len < 255
+            {
+                run_len += 1;
+            }
+
+            // a repeated run of 3+ indexes is always at least as short encoded
+            // as it is literal, so prefer that; otherwise fall back to an
+            // absolute run of literal indexes up to the next repeat (or end).
+            if run_len >= 3 || i + run_len == indexes.len() {
+                let second = if step == 4 {
+                    (indexes[i] << 4) | indexes[i]
+                } else {
+                    indexes[i]
+                };
+                out.push(run_len as u8);
+                out.push(second);
+                i += run_len;
+            } else {
+                let mut literal_len = 0;
+                let mut j = i;
+                while j < indexes.len() && literal_len < 255 {
+                    let mut repeat = 1;
+                    while j + repeat < indexes.len() && indexes[j + repeat] == indexes[j] && repeat < 255 {
+                        repeat += 1;
+                    }
+                    if repeat >= 3 || literal_len + repeat > 255 {
+                        break;
+                    }
+                    literal_len += repeat;
+                    j += repeat;
+                }
+
+                // absolute mode's count byte doubles as the escape code for
+                // end-of-line (0x00), end-of-bitmap (0x01), and delta (0x02),
+                // so it's only valid for runs of 3 or more literal indexes;
+                // shorter runs have to go out as one or two encoded runs.
+                if literal_len >= 3 {
+                    out.push(0x00);
+                    out.push(literal_len as u8);
+                    let bytes_used = if step == 4 {
+                        for pair in indexes[i..i + literal_len].chunks(2) {
+                            let high = pair[0];
+                            let low = *pair.get(1).unwrap_or(&0);
+                            out.push((high << 4) | low);
+                        }
+                        (literal_len + 1) / 2
+                    } else {
+                        out.extend_from_slice(&indexes[i..i + literal_len]);
+                        literal_len
+                    };
+                    if bytes_used % 2 != 0 {
+                        out.push(0);
+                    }
+                } else if step == 4 && literal_len == 2 {
+                    // one encoded run can carry two distinct 4-bit indexes
+                    out.push(2);
+                    out.push((indexes[i] << 4) | indexes[i + 1]);
+                } else {
+                    for k in 0..literal_len {
+                        let value = indexes[i + k];
+                        out.push(1);
+                        out.push(if step == 4 { value << 4 } else { value });
+                    }
+                }
+                i += literal_len;
+            }
+        }
+    }
+
+    ///
+    /// Create bit data from a `BI_BITFIELDS` stream of 16- or 32-bit
+    /// direct-color pixels. `bit_depth` must be `Sixteen` or `ThirtyTwo`;
+    /// `masks` gives the per-channel bit layout used to pull each pixel
+    /// apart in `as_rgba`.
+    ///
+    /// Validates the declared dimensions and `off_bits` offset against
+    /// `bit_stream` up front, the same way `stream` does for the indexed
+    /// path, instead of slicing blindly.
+    ///
+    pub fn from_bitfields(
+        bit_stream: &[u8],
+        file: &FileHeader,
+        info: &InfoHeader,
+        bit_depth: BitDepth,
+        masks: ChannelMasks,
+    ) -> Result<BitData, BitDataError> {
+        let width = info.get_width();
+        let height = info.get_height();
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(BitDataError::DimensionTooLarge { width, height });
+        }
+
+        let step = bit_depth.get_step_counter();
+        let row_bytes = Self::padded_row_bytes(width, step) as u64;
+        let expected_len = row_bytes
+            .checked_mul(height as u64)
+            .ok_or(BitDataError::SizeOverflow)?;
+
+        let offset = file.get_off_bits() as u64;
+        let required_end = offset
+            .checked_add(expected_len)
+            .ok_or(BitDataError::SizeOverflow)?;
+        if required_end > bit_stream.len() as u64 {
+            return Err(BitDataError::DataOutOfBounds {
+                required: required_end as usize,
+                available: bit_stream.len(),
+            });
+        }
+
+        let offset = offset as usize;
+        let expected_len = expected_len as usize;
+        let mut bytes = Vec::with_capacity(expected_len);
+        bytes.extend_from_slice(&bit_stream[offset..offset + expected_len]);
+
+        Ok(BitData {
+            width,
+            height,
+            bit_depth,
+            colors: Vec::new(),
+            bytes,
+            channel_masks: Some(masks),
+            top_down: info.is_top_down(),
+        })
+    }
+
+    ///
+    /// Create bit data from a bitmap by packing every pixel directly with
+    /// `masks` instead of going through a color table.
+    ///
+    pub fn from_bitmap_bitfields(bitmap: &BitMap, bit_depth: BitDepth, masks: ChannelMasks) -> BitData {
+        let step = bit_depth.get_step_counter();
+        let unit_bytes = (step / 8) as usize;
+        let row_bytes = Self::padded_row_bytes(bitmap.get_width(), step);
+        let mut bytes = Vec::with_capacity(row_bytes * bitmap.get_height() as usize);
+
+        // rows go out bottom-up, matching `top_down: false` below
+        for row in bitmap.get_pixels().chunks(bitmap.get_width() as usize).rev() {
+            let start = bytes.len();
+            for pixel in row {
+                let unit = Self::pack_channel(pixel.get_red(), masks.red)
+                    | Self::pack_channel(pixel.get_green(), masks.green)
+                    | Self::pack_channel(pixel.get_blue(), masks.blue)
+                    | if masks.alpha != 0 {
+                        Self::pack_channel(pixel.get_alpha(), masks.alpha)
+                    } else {
+                        0
+                    };
+                bytes.extend_from_slice(&unit.to_le_bytes()[..unit_bytes]);
+            }
+            while bytes.len() - start < row_bytes {
+                bytes.push(0);
+            }
+        }
+
+        BitData {
+            width: bitmap.get_width(),
+            height: bitmap.get_height(),
+            bit_depth,
+            colors: Vec::new(),
+            bytes,
+            channel_masks: Some(masks),
+            top_down: false,
+        }
+    }
+
+    /// Scale an 8-bit channel value up to the width of `mask` and shift it
+    /// into position.
+    fn pack_channel(channel: u8, mask: u32) -> u32 {
+        if mask == 0 {
+            return 0;
+        }
+        let shift = mask.trailing_zeros();
+        let width = (mask >> shift).trailing_ones().max(1);
+        let max = (1u32 << width) - 1;
+        let scaled = (channel as u32 * max) / 255;
+        (scaled << shift) & mask
+    }
+
+    /// Extract and scale an 8-bit channel value out of `unit` using `mask`.
+    /// Returns `default` (used for a missing alpha channel) when `mask` is 0.
+    fn unpack_channel(unit: u32, mask: u32, default: u8) -> u8 {
+        if mask == 0 {
+            return default;
+        }
+        let shift = mask.trailing_zeros();
+        let width = (mask >> shift).trailing_ones().max(1);
+        let max = (1u32 << width) - 1;
+        let extracted = (unit & mask) >> shift;
+        ((extracted * 255) / max) as u8
+    }
+
+    /// Decode a `BI_BITFIELDS` pixel stream into `Rgba`s using `masks`.
+    fn as_rgba_bitfields(&self, masks: &ChannelMasks) -> Vec<Rgba> {
+        let step = self.bit_depth.get_step_counter();
+        let unit_bytes = (step / 8) as usize;
+        let row_bytes = Self::padded_row_bytes(self.width, step);
+        let mut pixels = Vec::with_capacity((self.width * self.height) as usize);
+
+        for row in self.bytes.chunks(row_bytes) {
+            for unit_start in (0..(self.width as usize * unit_bytes)).step_by(unit_bytes) {
+                let mut unit = 0u32;
+                for (i, byte) in row[unit_start..unit_start + unit_bytes].iter().enumerate() {
+                    unit |= (*byte as u32) << (8 * i);
+                }
+                let red = Self::unpack_channel(unit, masks.red, 0);
+                let green = Self::unpack_channel(unit, masks.green, 0);
+                let blue = Self::unpack_channel(unit, masks.blue, 0);
+                let alpha = Self::unpack_channel(unit, masks.alpha, 255);
+                pixels.push(Rgba::rgba(red, green, blue, alpha));
+            }
+        }
+
+        // rows are stored bottom-up unless the info header's height was
+        // negative; `pixels` should always come out top-to-bottom
+        if !self.top_down {
+            let width = self.width as usize;
+            let mut top_to_bottom = Vec::with_capacity(pixels.len());
+            for row in pixels.chunks(width).rev() {
+                top_to_bottom.extend_from_slice(row);
+            }
+            pixels = top_to_bottom;
+        }
+        pixels
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -221,3 +861,90 @@ impl std::fmt::Display for BitData {
         write!(f, "")
     }
 }
+
+#[cfg(test)]
+mod rle_tests {
+    use super::BitData;
+
+    /// Decode a single `encode_row` run (ending in end-of-bitmap) back into
+    /// one index per pixel, mirroring the relevant part of
+    /// `from_rle_stream`'s escape handling.
+    fn decode_row(encoded: &[u8], step: u32, pixel_count: usize) -> Vec<u8> {
+        let mut decoded = vec![0u8; pixel_count];
+        let mut x = 0;
+        let mut i = 0;
+        while i + 1 < encoded.len() {
+            let first = encoded[i];
+            let second = encoded[i + 1];
+            i += 2;
+            if first != 0 {
+                let run_length = first as usize;
+                if step == 4 {
+                    let values = [second >> 4, second & 0x0F];
+                    for pixel in 0..run_length {
+                        decoded[x] = values[pixel % 2];
+                        x += 1;
+                    }
+                } else {
+                    for _ in 0..run_length {
+                        decoded[x] = second;
+                        x += 1;
+                    }
+                }
+                continue;
+            }
+            match second {
+                0x01 => break,
+                absolute_count => {
+                    let count = absolute_count as usize;
+                    let bytes_used = if step == 4 { (count + 1) / 2 } else { count };
+                    for pixel in 0..count {
+                        let value = if step == 4 {
+                            let byte = encoded[i + pixel / 2];
+                            if pixel % 2 == 0 {
+                                byte >> 4
+                            } else {
+                                byte & 0x0F
+                            }
+                        } else {
+                            encoded[i + pixel]
+                        };
+                        decoded[x] = value;
+                        x += 1;
+                    }
+                    i += bytes_used + (bytes_used % 2);
+                }
+            }
+        }
+        decoded
+    }
+
+    fn assert_round_trips(indexes: &[u8], step: u32) {
+        let mut encoded = Vec::new();
+        BitData::encode_row(indexes, step, &mut encoded);
+        encoded.push(0x00);
+        encoded.push(0x01); // end of bitmap
+        assert_eq!(decode_row(&encoded, step, indexes.len()), indexes);
+    }
+
+    #[test]
+    fn rle8_round_trips_short_literal_runs() {
+        // a 1- or 2-pixel literal run ahead of a 3+ run used to be emitted
+        // as an absolute count of 1 or 2, colliding with the end-of-bitmap
+        // and delta escape codes.
+        assert_round_trips(&[10, 20], 8);
+        assert_round_trips(&[1, 2, 3, 3, 3], 8);
+        assert_round_trips(&[5, 5, 5, 1, 2, 3, 3, 3, 9], 8);
+    }
+
+    #[test]
+    fn rle4_round_trips_short_literal_runs() {
+        assert_round_trips(&[1, 2], 4);
+        assert_round_trips(&[1, 2, 3, 3, 3], 4);
+    }
+
+    #[test]
+    fn rle8_round_trips_long_literal_run() {
+        assert_round_trips(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 8);
+    }
+}
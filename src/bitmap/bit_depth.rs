@@ -0,0 +1,37 @@
+///
+/// The number of bits used to store each pixel (or, for the direct-color
+/// depths, each channel-packed unit) of a bitmap.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    One,
+    Four,
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+impl BitDepth {
+    ///
+    /// Number of bits each `BitData` entry advances by: the index width for
+    /// the palette depths, or the packed-pixel width for the direct-color
+    /// depths.
+    ///
+    pub fn get_step_counter(&self) -> u32 {
+        match self {
+            BitDepth::One => 1,
+            BitDepth::Four => 4,
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+            BitDepth::ThirtyTwo => 32,
+        }
+    }
+
+    ///
+    /// Whether this depth addresses pixels through a color table rather
+    /// than storing channel values directly (`BI_BITFIELDS`).
+    ///
+    pub fn is_indexed(&self) -> bool {
+        matches!(self, BitDepth::One | BitDepth::Four | BitDepth::Eight)
+    }
+}
@@ -0,0 +1,172 @@
+use super::bit_data::BitData;
+use super::bit_depth::BitDepth;
+use super::image::BitMap;
+use super::rgba::Rgba;
+
+const ICONDIR_SIZE: usize = 6;
+const ICONDIRENTRY_SIZE: usize = 16;
+const BITMAPINFOHEADER_SIZE: u32 = 40;
+
+///
+/// `IcoImage::new` was asked for a bit depth `encode_image` can't produce:
+/// ICO entries are written through `BitData::from_bitmap`'s indexed path,
+/// so only the 1/4/8-bit palette depths are supported, not the 16/32-bit
+/// `BI_BITFIELDS` direct-color depths.
+///
+#[derive(Debug)]
+pub struct UnsupportedIcoDepth(pub BitDepth);
+
+impl std::fmt::Display for UnsupportedIcoDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ICO export only supports indexed bit depths, got {:?}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedIcoDepth {}
+
+///
+/// One image to be packed into an ICO/CUR file, at the bit depth it should
+/// be stored with.
+///
+pub struct IcoImage<'a> {
+    bitmap: &'a BitMap,
+    bit_depth: BitDepth,
+}
+
+impl<'a> IcoImage<'a> {
+    pub fn new(bitmap: &'a BitMap, bit_depth: BitDepth) -> Result<IcoImage<'a>, UnsupportedIcoDepth> {
+        if !bit_depth.is_indexed() {
+            return Err(UnsupportedIcoDepth(bit_depth));
+        }
+        Ok(IcoImage { bitmap, bit_depth })
+    }
+}
+
+///
+/// Write one or more images out as a Windows `.ico` file: an ICONDIR
+/// header, a directory entry per image, then each image as a headerless
+/// BMP (BITMAPINFOHEADER with doubled height, optional color table, XOR
+/// pixel data, and a synthesized 1-bpp AND transparency mask).
+///
+pub fn write_ico(images: &[IcoImage]) -> Vec<u8> {
+    let encoded: Vec<Vec<u8>> = images.iter().map(encode_image).collect();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&0u16.to_le_bytes()); // reserved, must be 0
+    out.extend_from_slice(&1u16.to_le_bytes()); // image type: 1 = icon
+    out.extend_from_slice(&(encoded.len() as u16).to_le_bytes());
+
+    let mut offset = ICONDIR_SIZE + ICONDIRENTRY_SIZE * encoded.len();
+    for (image, data) in images.iter().zip(&encoded) {
+        let width = image.bitmap.get_width();
+        let height = image.bitmap.get_height();
+        out.push(if width >= 256 { 0 } else { width as u8 });
+        out.push(if height >= 256 { 0 } else { height as u8 });
+        out.push(0); // color count: written as 0 for every depth
+        out.push(0); // reserved
+        out.extend_from_slice(&1u16.to_le_bytes()); // color planes
+        out.extend_from_slice(&(image.bit_depth.get_step_counter() as u16).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += data.len();
+    }
+
+    for data in &encoded {
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// Encode one image as a headerless BMP suitable for an ICONDIRENTRY: info
+/// header, color table (if indexed), XOR pixel data, then the AND mask.
+fn encode_image(image: &IcoImage) -> Vec<u8> {
+    let bit_data = BitData::from_bitmap(image.bitmap, image.bit_depth);
+    let xor_bytes = bit_data.as_bytes();
+    let and_bytes = and_mask_bytes(image.bitmap);
+
+    let indexed = image.bit_depth.is_indexed();
+    let colors_used = if indexed {
+        bit_data.get_colors().len() as u32
+    } else {
+        0
+    };
+
+    let mut out = info_header_bytes(
+        image.bitmap.get_width(),
+        image.bitmap.get_height(),
+        image.bit_depth,
+        colors_used,
+        (xor_bytes.len() + and_bytes.len()) as u32,
+    );
+    if indexed {
+        out.extend_from_slice(&palette_bytes(bit_data.get_colors()));
+    }
+    out.extend_from_slice(&xor_bytes);
+    out.extend_from_slice(&and_bytes);
+    out
+}
+
+/// A BITMAPINFOHEADER for an ICO entry: same layout as a normal BMP's, but
+/// `height` is doubled to account for the AND mask stacked below the XOR
+/// data.
+fn info_header_bytes(
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    colors_used: u32,
+    image_size: u32,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BITMAPINFOHEADER_SIZE as usize);
+    out.extend_from_slice(&BITMAPINFOHEADER_SIZE.to_le_bytes());
+    out.extend_from_slice(&(width as i32).to_le_bytes());
+    out.extend_from_slice(&((height * 2) as i32).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // color planes
+    out.extend_from_slice(&(bit_depth.get_step_counter() as u16).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // compression: BI_RGB
+    out.extend_from_slice(&image_size.to_le_bytes());
+    out.extend_from_slice(&0i32.to_le_bytes()); // x pixels per meter
+    out.extend_from_slice(&0i32.to_le_bytes()); // y pixels per meter
+    out.extend_from_slice(&colors_used.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // colors important
+    out
+}
+
+/// Pack a palette into BMP color table entries (blue, green, red, reserved).
+fn palette_bytes(colors: &[Rgba]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(colors.len() * 4);
+    for color in colors {
+        out.push(color.get_blue());
+        out.push(color.get_green());
+        out.push(color.get_red());
+        out.push(0);
+    }
+    out
+}
+
+/// Build the 1-bpp AND transparency mask: one bit per pixel (set = fully
+/// transparent), rows padded to a 4-byte boundary and stored bottom-up like
+/// the rest of the BMP pixel data.
+fn and_mask_bytes(bitmap: &BitMap) -> Vec<u8> {
+    let width = bitmap.get_width();
+    let height = bitmap.get_height();
+    let row_bytes = (((width + 31) / 32) * 4) as usize;
+    let mut out = vec![0u8; row_bytes * height as usize];
+
+    let pixels = bitmap.get_pixels();
+    for y in 0..height {
+        let source_row = height - 1 - y; // bottom-up
+        for x in 0..width {
+            let pixel = pixels[(source_row * width + x) as usize];
+            if pixel.get_alpha() == 0 {
+                let byte_index = y as usize * row_bytes + (x / 8) as usize;
+                let bit = 7 - (x % 8);
+                out[byte_index] |= 1 << bit;
+            }
+        }
+    }
+    out
+}